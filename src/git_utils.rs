@@ -1,13 +1,315 @@
-use io::Result;
+use std::fmt;
 use std::io;
-use std::io::{ErrorKind, Error};
-use std::path::PathBuf;
-use std::process::{Command, Output};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
 
 use crate::CliArgs;
 use crate::log::Logger;
 
-const ERR_MSG: &str = "Error executing git command";
+pub type Result<T> = std::result::Result<T, GitError>;
+
+/// Error produced by a failed git operation.
+///
+/// Unlike a generic `io::Error`, the variants for a failed command
+/// (`Subcommand`, `Git`, `InvalidOutput`) carry the subcommand that was being
+/// run so callers (and the CLI) can report precisely what failed.
+#[derive(Debug)]
+pub enum GitError {
+    /// The `git` process itself could not be spawned (e.g. binary not on `PATH`).
+    Subcommand { subcommand: &'static str, source: io::Error },
+    /// `git` ran but exited with a non-zero status.
+    Git { subcommand: &'static str, status: ExitStatus, stderr: String },
+    /// git produced output that was not valid UTF-8.
+    InvalidOutput { subcommand: &'static str, source: std::string::FromUtf8Error },
+    /// The requested branch name already has a ref pointing at it.
+    BranchExists { name: String },
+    /// The requested branch name fails git's ref-format rules.
+    InvalidBranchName { name: String, reason: String },
+    /// The requested branch name collides with an existing branch along the
+    /// `/`-separated namespace (e.g. `foo` vs. `foo/bar`).
+    BranchPathConflict { name: String, existing: String },
+    /// `project_path` is neither a git nor a Mercurial working copy.
+    UnknownBackend { path: PathBuf },
+    /// The requested operation has no equivalent for the active backend.
+    UnsupportedOperation { operation: &'static str, backend: Backend },
+    /// A path argument was not valid UTF-8.
+    InvalidPath { path: PathBuf },
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitError::Subcommand { subcommand, source } => {
+                write!(f, "failed to run `git {}`: {}", subcommand, source)
+            }
+            GitError::Git { subcommand, status, stderr } => {
+                write!(f, "`git {}` failed ({}): {}", subcommand, status, stderr.trim())
+            }
+            GitError::InvalidOutput { subcommand, source } => {
+                write!(f, "`git {}` produced non-UTF-8 output: {}", subcommand, source)
+            }
+            GitError::BranchExists { name } => write!(f, "branch {} already exists!", name),
+            GitError::InvalidBranchName { name, reason } => {
+                write!(f, "branch name {} is invalid: {}", name, reason)
+            }
+            GitError::BranchPathConflict { name, existing } => {
+                write!(f, "branch name {} conflicts with existing branch {}", name, existing)
+            }
+            GitError::UnknownBackend { path } => {
+                write!(f, "{} is neither a git nor a Mercurial working copy", path.display())
+            }
+            GitError::UnsupportedOperation { operation, backend } => {
+                write!(f, "{} is not supported for the {:?} backend", operation, backend)
+            }
+            GitError::InvalidPath { path } => {
+                write!(f, "{} is not valid UTF-8", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for GitError {}
+
+/// Checks `name` against git's ref-format rules (see `git check-ref-format(1)`).
+///
+/// This only covers the syntactic rules; namespace collisions with existing
+/// branches (e.g. `foo` vs. `foo/bar`) are handled separately by
+/// `Repository::check_branch_path_conflict`.
+fn validate_refname(name: &str) -> Result<()> {
+    let reason = if name.is_empty() {
+        Some("name is empty".to_string())
+    } else if name.contains("..") {
+        Some("contains '..'".to_string())
+    } else if name.chars().any(|c| c.is_ascii_control() || c == ' ') {
+        Some("contains a control character or space".to_string())
+    } else if name.chars().any(|c| "~^:?*[\\".contains(c)) {
+        Some("contains one of '~ ^ : ? * [ \\'".to_string())
+    } else if name.starts_with('/') || name.ends_with('/') {
+        Some("begins or ends with '/'".to_string())
+    } else if name.contains("//") {
+        Some("contains '//'".to_string())
+    } else if name.ends_with('.') {
+        Some("ends with '.'".to_string())
+    } else if name.ends_with(".lock") {
+        Some("ends with '.lock'".to_string())
+    } else if name.contains("@{") {
+        Some("contains '@{'".to_string())
+    } else if name == "@" {
+        Some("is exactly '@'".to_string())
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => Err(GitError::InvalidBranchName { name: name.to_string(), reason }),
+        None => Ok(()),
+    }
+}
+
+/// The version-control system a `Repository` talks to.
+///
+/// Every backend-specific command shape (how to read the current branch, how
+/// to create a new one, ...) lives behind this enum so `Repository` itself
+/// stays VCS-agnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Git,
+    Mercurial,
+}
+
+impl Backend {
+    /// Detects the backend by looking for `.git` or `.hg` in `project_path`.
+    fn detect(project_path: &Path) -> Result<Self> {
+        if project_path.join(".git").exists() {
+            Ok(Backend::Git)
+        } else if project_path.join(".hg").exists() {
+            Ok(Backend::Mercurial)
+        } else {
+            Err(GitError::UnknownBackend { path: project_path.to_path_buf() })
+        }
+    }
+
+    fn executable(&self) -> &'static str {
+        match self {
+            Backend::Git => "git",
+            Backend::Mercurial => "hg",
+        }
+    }
+
+    fn repo_flag(&self) -> &'static str {
+        match self {
+            Backend::Git => "-C",
+            Backend::Mercurial => "-R",
+        }
+    }
+}
+
+/// Snapshot of repository version/build metadata, e.g. for embedding a
+/// Maven version or build stamp such as `1.2.0-a1b2c3d-dirty`.
+#[derive(Debug, Clone)]
+pub struct RepositoryMetadata {
+    short_hash: String,
+    full_hash: String,
+    nearest_tag: Option<String>,
+    commit_date: String,
+    author_name: String,
+    author_email: String,
+    dirty: bool,
+}
+
+impl RepositoryMetadata {
+    pub fn short_hash(&self) -> &str {
+        &self.short_hash
+    }
+
+    pub fn full_hash(&self) -> &str {
+        &self.full_hash
+    }
+
+    pub fn nearest_tag(&self) -> Option<&str> {
+        self.nearest_tag.as_deref()
+    }
+
+    pub fn commit_date(&self) -> &str {
+        &self.commit_date
+    }
+
+    pub fn author_name(&self) -> &str {
+        &self.author_name
+    }
+
+    pub fn author_email(&self) -> &str {
+        &self.author_email
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Builds a Maven-style version stamp, e.g. `1.2.0-a1b2c3d` or
+    /// `1.2.0-a1b2c3d-dirty`.
+    pub fn stamp_version(&self, base_version: &str) -> String {
+        if self.dirty {
+            format!("{}-{}-dirty", base_version, self.short_hash)
+        } else {
+            format!("{}-{}", base_version, self.short_hash)
+        }
+    }
+}
+
+/// Builds a `Git` handle, letting callers accumulate persistent global
+/// arguments (`--git-dir`, `--work-tree`, `-c <key>=<value>`, ...) that get
+/// prepended to every subsequent command, right after the `["-C", path]`
+/// (`["-R", path]` for Mercurial) that every command gets.
+pub struct GitBuilder {
+    project_path: PathBuf,
+    debug: bool,
+    backend: Option<Backend>,
+    global_args: Vec<String>,
+    git_dir: Option<PathBuf>,
+    work_tree: Option<PathBuf>,
+    config: Vec<(String, String)>,
+}
+
+impl GitBuilder {
+    pub fn new(project_path: PathBuf, args: &CliArgs) -> Self {
+        GitBuilder {
+            project_path,
+            debug: args.debug,
+            backend: None,
+            global_args: Vec::new(),
+            git_dir: None,
+            work_tree: None,
+            config: Vec::new(),
+        }
+    }
+
+    /// Forces a specific `Backend` instead of auto-detecting it from
+    /// `project_path`. Useful when detection would be ambiguous (e.g. a
+    /// directory containing both `.git` and `.hg`) or simply wrong.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Appends a raw global argument, e.g. `--no-pager`.
+    pub fn global_arg(mut self, arg: impl Into<String>) -> Self {
+        self.global_args.push(arg.into());
+        self
+    }
+
+    /// Points at a separate git directory, for operating against a bare
+    /// repository. Git-only; fails at `open()` time for other backends.
+    pub fn git_dir(mut self, git_dir: impl AsRef<Path>) -> Self {
+        self.git_dir = Some(git_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Points at a separate work tree, for operating against a linked
+    /// worktree. Git-only; fails at `open()` time for other backends.
+    pub fn work_tree(mut self, work_tree: impl AsRef<Path>) -> Self {
+        self.work_tree = Some(work_tree.as_ref().to_path_buf());
+        self
+    }
+
+    /// Overrides a config value for this invocation only. Translated to
+    /// `-c <key>=<value>` for git and `--config <key>=<value>` for Mercurial.
+    pub fn config(mut self, key: &str, value: &str) -> Self {
+        self.config.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn open(self) -> Result<Git> {
+        let backend = match self.backend {
+            Some(backend) => backend,
+            None => Backend::detect(&self.project_path)?,
+        };
+
+        let mut extra_args = Vec::new();
+
+        if let Some(git_dir) = &self.git_dir {
+            match backend {
+                Backend::Git => {
+                    extra_args.push("--git-dir".to_string());
+                    extra_args.push(Repository::path_str(git_dir)?.to_string());
+                }
+                Backend::Mercurial => {
+                    return Err(GitError::UnsupportedOperation { operation: "git_dir", backend });
+                }
+            }
+        }
+
+        if let Some(work_tree) = &self.work_tree {
+            match backend {
+                Backend::Git => {
+                    extra_args.push("--work-tree".to_string());
+                    extra_args.push(Repository::path_str(work_tree)?.to_string());
+                }
+                Backend::Mercurial => {
+                    return Err(GitError::UnsupportedOperation { operation: "work_tree", backend });
+                }
+            }
+        }
+
+        for (key, value) in &self.config {
+            match backend {
+                Backend::Git => {
+                    extra_args.push("-c".to_string());
+                    extra_args.push(format!("{}={}", key, value));
+                }
+                Backend::Mercurial => {
+                    extra_args.push("--config".to_string());
+                    extra_args.push(format!("{}={}", key, value));
+                }
+            }
+        }
+
+        extra_args.extend(self.global_args);
+
+        let repo = Repository::open(self.project_path, backend, extra_args)?;
+        Ok(Git::wrap(repo, self.debug))
+    }
+}
 
 pub struct Git {
     log: Logger,
@@ -16,44 +318,73 @@ pub struct Git {
 
 #[derive(Debug)]
 pub struct Repository {
-    project_path: PathBuf
+    backend: Backend,
+    /// Arguments prepended to every command spawned for this repository,
+    /// e.g. `["-C", "/path", "--git-dir", "/path/.git"]`.
+    global_args: Vec<String>,
 }
 
 trait OutputHandler {
-    fn handle_git_cmd(self) -> Result<String>;
+    fn handle_git_cmd(self, subcommand: &'static str) -> Result<String>;
 }
 
 impl OutputHandler for Output {
-    fn handle_git_cmd(self) -> Result<String> {
-        if !self.status.success() && !self.stderr.is_empty() {
-            let err_msg = match String::from_utf8(self.stderr) {
-                Ok(msg) => msg,
-                Err(e) => e.to_string()
-            };
-
-            Err(
-                Error::new(ErrorKind::InvalidData, err_msg)
-            )
-        } else {
-            Ok(String::from_utf8(self.stdout).unwrap())
+    fn handle_git_cmd(self, subcommand: &'static str) -> Result<String> {
+        if !self.status.success() {
+            let stderr = String::from_utf8_lossy(&self.stderr).into_owned();
+            return Err(GitError::Git { subcommand, status: self.status, stderr });
         }
+
+        String::from_utf8(self.stdout).map_err(|source| GitError::InvalidOutput { subcommand, source })
     }
 }
 
 impl Git {
     pub fn open(project_path: PathBuf, args: &CliArgs) -> Result<Self> {
-        let repo = Repository::open(project_path)?;
-        Ok(
-            Git {
-                log: Logger::new(args.debug, "git-utils"),
-                repository: repo,
-            }
-        )
+        GitBuilder::new(project_path, args).open()
+    }
+
+    pub fn builder(project_path: PathBuf, args: &CliArgs) -> GitBuilder {
+        GitBuilder::new(project_path, args)
+    }
+
+    /// Clones `url` into `dest` (recursing into submodules) and opens it.
+    pub fn clone(url: &str, dest: PathBuf, args: &CliArgs) -> Result<Self> {
+        let repo = Repository::clone(url, &dest)?;
+        Ok(Git::wrap(repo, args.debug))
+    }
+
+    /// Initializes a new repository in `dir` with the given initial branch
+    /// name (defaulting to `main`) and opens it.
+    pub fn init(dir: PathBuf, default_branch: Option<&str>, args: &CliArgs) -> Result<Self> {
+        let repo = Repository::init(&dir, default_branch.unwrap_or("main"))?;
+        Ok(Git::wrap(repo, args.debug))
+    }
+
+    fn wrap(repository: Repository, debug: bool) -> Self {
+        Git {
+            log: Logger::new(debug, "git-utils"),
+            repository,
+        }
     }
 
     pub fn new_branch(&self, b_name: &str) -> Result<()> {
+        validate_refname(b_name)?;
+
         self.log.info(format!("Checking if branch {} already exists...", b_name).as_str());
-        self.repository.branch_exists(b_name)?;
+        let existing_branches = self.repository.existing_branches()?;
+        self.repository.branch_exists(b_name, &existing_branches)?;
+        self.repository.check_branch_path_conflict(b_name, &existing_branches)?;
+
+        let dirty_files = self.repository.dirty_files()?;
+        if !dirty_files.is_empty() {
+            self.log.info(
+                format!(
+                    "Working tree has uncommitted changes that will be carried onto {}: {}",
+                    b_name, dirty_files.join(", ")
+                ).as_str()
+            );
+        }
 
         let current_branch = self.repository.current_branch()?;
         self.log.info(
@@ -67,48 +398,353 @@ impl Git {
 
         Ok(())
     }
+
+    pub fn metadata(&self) -> Result<RepositoryMetadata> {
+        self.repository.metadata()
+    }
+
+    /// Whether the working tree has no uncommitted changes.
+    pub fn tree_is_clean(&self) -> Result<bool> {
+        self.repository.tree_is_clean()
+    }
+
+    /// Lists paths with uncommitted changes in the working tree.
+    pub fn dirty_files(&self) -> Result<Vec<String>> {
+        self.repository.dirty_files()
+    }
 }
 
 impl Repository {
-    fn open(project_path: PathBuf) -> Result<Self> {
-        Command::new("git")
-            .args(&["-C", project_path.to_str().unwrap(), "rev-parse"])
+    fn run(&self, subcommand: &'static str, args: &[&str]) -> Result<String> {
+        let output = Command::new(self.backend.executable())
+            .args(&self.global_args)
+            .args(args)
             .output()
-            .expect(ERR_MSG)
-            .handle_git_cmd()?;
+            .map_err(|source| GitError::Subcommand { subcommand, source })?;
 
-        Ok(Repository { project_path })
+        output.handle_git_cmd(subcommand)
     }
 
-    fn new_branch(&self, b_name: &str, current_branch: &str) -> Result<String> {
-        Command::new("git")
-            .args(&["-C", self.project_path.to_str().unwrap(), "checkout", "-b", b_name, current_branch])
+    /// Clones `url` into `dest` (recursing into submodules), then opens it.
+    ///
+    /// The `--` separator keeps a `url`/`dest` that happens to start with
+    /// `-` from being parsed as a git option.
+    fn clone(url: &str, dest: &Path) -> Result<Self> {
+        let dest_str = Repository::path_str(dest)?;
+
+        Command::new(Backend::Git.executable())
+            .args(["clone", "--recursive", "--", url, dest_str])
             .output()
-            .expect(ERR_MSG)
-            .handle_git_cmd()
+            .map_err(|source| GitError::Subcommand { subcommand: "clone", source })?
+            .handle_git_cmd("clone")?;
+
+        Repository::open(dest.to_path_buf(), Backend::Git, Vec::new())
     }
 
-    fn current_branch(&self) -> Result<String> {
-        Command::new("git")
-            .args(&["-C", self.project_path.to_str().unwrap(), "rev-parse", "--abbrev-ref", "HEAD"])
+    /// Initializes a fresh git repository in `dir` with `default_branch` as
+    /// its initial branch, then opens it.
+    fn init(dir: &Path, default_branch: &str) -> Result<Self> {
+        validate_refname(default_branch)?;
+        let dir_str = Repository::path_str(dir)?;
+
+        Command::new(Backend::Git.executable())
+            .args(["init", format!("--initial-branch={}", default_branch).as_str(), "--", dir_str])
             .output()
-            .expect(ERR_MSG)
-            .handle_git_cmd()
+            .map_err(|source| GitError::Subcommand { subcommand: "init", source })?
+            .handle_git_cmd("init")?;
+
+        Repository::open(dir.to_path_buf(), Backend::Git, Vec::new())
     }
 
-    fn branch_exists(&self, b_name: &str) -> Result<String> {
-        let res = Command::new("git")
-            .args(&["-C", self.project_path.to_str().unwrap(), "show-ref", format!("refs/heads/{}", b_name).as_str()])
-            .output()
-            .expect(ERR_MSG)
-            .handle_git_cmd()?;
+    fn path_str(path: &Path) -> Result<&str> {
+        path.to_str().ok_or_else(|| GitError::InvalidPath { path: path.to_path_buf() })
+    }
 
-        if !res.is_empty() {
-            Err(
-                Error::new(ErrorKind::InvalidData, format!("Branch {} already exists!", b_name))
-            )
+    fn open(project_path: PathBuf, backend: Backend, extra_global_args: Vec<String>) -> Result<Self> {
+        let mut global_args = vec![backend.repo_flag().to_string(), Repository::path_str(&project_path)?.to_string()];
+        global_args.extend(extra_global_args);
+
+        let repo = Repository { backend, global_args };
+        match backend {
+            Backend::Git => repo.run("rev-parse", &["rev-parse"])?,
+            Backend::Mercurial => repo.run("root", &["root"])?,
+        };
+        Ok(repo)
+    }
+
+    fn new_branch(&self, b_name: &str, current_branch: &str) -> Result<String> {
+        match self.backend {
+            // On an unborn HEAD (e.g. right after `git init`, before the
+            // first commit) `current_branch` has no commit to branch from
+            // yet, so `checkout -b <name> <current_branch>` would fail with
+            // "invalid reference". Omitting the starting point instead just
+            // renames the unborn branch, which is what we want here.
+            Backend::Git if self.is_unborn_head()? => self.run("checkout", &["checkout", "-b", b_name]),
+            Backend::Git => self.run("checkout", &["checkout", "-b", b_name, current_branch]),
+            Backend::Mercurial => self.run("branch", &["branch", b_name]),
+        }
+    }
+
+    /// Whether `HEAD` has no commit yet (a fresh `git init` with nothing
+    /// committed). `git rev-parse --abbrev-ref HEAD` errors in this state,
+    /// so `current_branch` reads the branch name via `symbolic-ref` instead,
+    /// which works whether or not `HEAD` has a commit behind it.
+    fn is_unborn_head(&self) -> Result<bool> {
+        match self.run("rev-parse", &["rev-parse", "--verify", "--quiet", "HEAD"]) {
+            Ok(_) => Ok(false),
+            Err(GitError::Git { status, .. }) if status.code() == Some(1) => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn current_branch(&self) -> Result<String> {
+        match self.backend {
+            Backend::Git => self.run("symbolic-ref", &["symbolic-ref", "--short", "HEAD"]),
+            Backend::Mercurial => self.run("branch", &["branch"]),
+        }
+    }
+
+    fn branch_exists(&self, b_name: &str, existing_branches: &[String]) -> Result<()> {
+        if existing_branches.iter().any(|b| b == b_name) {
+            Err(GitError::BranchExists { name: b_name.to_string() })
         } else {
-            Ok(res)
+            Ok(())
+        }
+    }
+
+    /// Lists the names of all local branches.
+    fn existing_branches(&self) -> Result<Vec<String>> {
+        match self.backend {
+            Backend::Git => {
+                let out = self.run("show-ref", &["show-ref", "--heads"])
+                    .or_else(|err| match err {
+                        // show-ref exits with status 1 when there are simply no
+                        // matching refs (e.g. a fresh repo with no commits yet).
+                        // Any other status (e.g. 128 for a corrupt ref) is a
+                        // real failure and must propagate.
+                        GitError::Git { status, .. } if status.code() == Some(1) => Ok(String::new()),
+                        err => Err(err),
+                    })?;
+
+                Ok(
+                    out.lines()
+                        .filter_map(|line| line.split_whitespace().nth(1))
+                        .filter_map(|refname| refname.strip_prefix("refs/heads/"))
+                        .map(String::from)
+                        .collect()
+                )
+            }
+            Backend::Mercurial => {
+                let out = self.run("branches", &["branches", "--closed"])?;
+
+                Ok(
+                    out.lines()
+                        .filter_map(|line| line.split_whitespace().next())
+                        .map(String::from)
+                        .collect()
+                )
+            }
+        }
+    }
+
+    /// Whether the working tree has no uncommitted changes.
+    fn tree_is_clean(&self) -> Result<bool> {
+        Ok(self.dirty_files()?.is_empty())
+    }
+
+    /// Lists paths with uncommitted changes in the working tree.
+    fn dirty_files(&self) -> Result<Vec<String>> {
+        match self.backend {
+            Backend::Git => {
+                let out = self.run("status", &["status", "--porcelain"])?;
+
+                Ok(
+                    out.lines()
+                        .filter(|line| line.len() > 3)
+                        .map(|line| line[3..].trim().to_string())
+                        .collect()
+                )
+            }
+            Backend::Mercurial => {
+                let out = self.run("status", &["status"])?;
+
+                Ok(
+                    out.lines()
+                        .filter(|line| line.len() > 2)
+                        .map(|line| line[2..].trim().to_string())
+                        .collect()
+                )
+            }
+        }
+    }
+
+    /// Gathers version/build metadata about the current commit. Only
+    /// supported for the `Git` backend.
+    fn metadata(&self) -> Result<RepositoryMetadata> {
+        if self.backend != Backend::Git {
+            return Err(GitError::UnsupportedOperation { operation: "metadata", backend: self.backend });
+        }
+
+        let full_hash = self.run("rev-parse", &["rev-parse", "HEAD"])?.trim().to_string();
+        let short_hash = self.run("rev-parse", &["rev-parse", "--short", "HEAD"])?.trim().to_string();
+        let nearest_tag = self.run("describe", &["describe", "--tags", "--abbrev=0"])
+            .ok()
+            .map(|tag| tag.trim().to_string());
+
+        let log_line = self.run("log", &["log", "-1", "--format=%ad\x1f%an\x1f%ae", "--date=iso-strict"])?;
+        let mut fields = log_line.trim().splitn(3, '\u{1f}');
+        let commit_date = fields.next().unwrap_or_default().to_string();
+        let author_name = fields.next().unwrap_or_default().to_string();
+        let author_email = fields.next().unwrap_or_default().to_string();
+
+        let dirty = !self.tree_is_clean()?;
+
+        Ok(
+            RepositoryMetadata {
+                short_hash,
+                full_hash,
+                nearest_tag,
+                commit_date,
+                author_name,
+                author_email,
+                dirty,
+            }
+        )
+    }
+
+    /// Rejects `b_name` if it collides with an existing branch along the
+    /// `/`-separated namespace, the same way `git branch` itself does:
+    /// creating `foo` must fail if `foo/bar` exists, and vice versa.
+    fn check_branch_path_conflict(&self, b_name: &str, existing_branches: &[String]) -> Result<()> {
+        match find_path_conflict(b_name, existing_branches) {
+            Some(existing) => Err(GitError::BranchPathConflict { name: b_name.to_string(), existing: existing.to_string() }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Finds the first branch in `existing_branches` whose `/`-separated
+/// namespace collides with `b_name` (an exact match is not a conflict).
+fn find_path_conflict<'a>(b_name: &str, existing_branches: &'a [String]) -> Option<&'a str> {
+    existing_branches.iter().find_map(|existing| {
+        if existing == b_name {
+            return None;
+        }
+
+        let is_prefix_conflict = existing.starts_with(&format!("{}/", b_name))
+            || b_name.starts_with(&format!("{}/", existing));
+
+        is_prefix_conflict.then_some(existing.as_str())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_refname_accepts_ordinary_names() {
+        assert!(validate_refname("main").is_ok());
+        assert!(validate_refname("feature/foo").is_ok());
+    }
+
+    #[test]
+    fn validate_refname_rejects_empty() {
+        assert!(validate_refname("").is_err());
+    }
+
+    #[test]
+    fn validate_refname_rejects_dot_dot() {
+        assert!(validate_refname("foo..bar").is_err());
+    }
+
+    #[test]
+    fn validate_refname_rejects_control_char_or_space() {
+        assert!(validate_refname("foo bar").is_err());
+        assert!(validate_refname("foo\tbar").is_err());
+    }
+
+    #[test]
+    fn validate_refname_rejects_special_chars() {
+        for c in ["~", "^", ":", "?", "*", "[", "\\"] {
+            assert!(validate_refname(&format!("foo{}bar", c)).is_err(), "expected {:?} to be rejected", c);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn validate_refname_rejects_leading_or_trailing_slash() {
+        assert!(validate_refname("/foo").is_err());
+        assert!(validate_refname("foo/").is_err());
+    }
+
+    #[test]
+    fn validate_refname_rejects_double_slash() {
+        assert!(validate_refname("foo//bar").is_err());
+    }
+
+    #[test]
+    fn validate_refname_rejects_trailing_dot() {
+        assert!(validate_refname("foo.").is_err());
+    }
+
+    #[test]
+    fn validate_refname_rejects_dot_lock_suffix() {
+        assert!(validate_refname("foo.lock").is_err());
+    }
+
+    #[test]
+    fn validate_refname_rejects_at_brace() {
+        assert!(validate_refname("foo@{bar}").is_err());
+    }
+
+    #[test]
+    fn validate_refname_rejects_bare_at() {
+        assert!(validate_refname("@").is_err());
+    }
+
+    #[test]
+    fn find_path_conflict_detects_existing_child() {
+        let existing = vec!["foo/bar".to_string()];
+        assert_eq!(find_path_conflict("foo", &existing), Some("foo/bar"));
+    }
+
+    #[test]
+    fn find_path_conflict_detects_existing_parent() {
+        let existing = vec!["foo".to_string()];
+        assert_eq!(find_path_conflict("foo/bar", &existing), Some("foo"));
+    }
+
+    #[test]
+    fn find_path_conflict_allows_exact_match() {
+        let existing = vec!["foo".to_string()];
+        assert_eq!(find_path_conflict("foo", &existing), None);
+    }
+
+    #[test]
+    fn find_path_conflict_allows_unrelated_siblings() {
+        let existing = vec!["foo".to_string(), "foobar".to_string()];
+        assert_eq!(find_path_conflict("foo2", &existing), None);
+    }
+
+    fn sample_metadata(dirty: bool) -> RepositoryMetadata {
+        RepositoryMetadata {
+            short_hash: "a1b2c3d".to_string(),
+            full_hash: "a1b2c3d000000000000000000000000000000".to_string(),
+            nearest_tag: None,
+            commit_date: "2024-01-01T00:00:00+00:00".to_string(),
+            author_name: "Jane Doe".to_string(),
+            author_email: "jane@example.com".to_string(),
+            dirty,
+        }
+    }
+
+    #[test]
+    fn stamp_version_appends_short_hash() {
+        assert_eq!(sample_metadata(false).stamp_version("1.2.0"), "1.2.0-a1b2c3d");
+    }
+
+    #[test]
+    fn stamp_version_marks_dirty_tree() {
+        assert_eq!(sample_metadata(true).stamp_version("1.2.0"), "1.2.0-a1b2c3d-dirty");
+    }
+}